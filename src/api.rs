@@ -1,18 +1,29 @@
 use core::time::Duration;
 use std::iter;
 use std::sync::Arc;
+use std::time::Instant;
 
 use actix_web::http;
+use actix_web::middleware::Next;
 use actix_web::rt;
 use actix_web::web;
+use actix_web::HttpRequest;
 use actix_web::HttpResponse;
 use arcerror::ArcError;
 use arcstr::ArcStr;
+use dashmap::DashMap;
 use dkregistry::v2::Client;
 use futures::stream;
 use futures::StreamExt;
 use futures::TryStreamExt;
+use metrics::counter;
+use metrics::gauge;
+use metrics::histogram;
+use metrics_exporter_prometheus::PrometheusHandle;
 use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
 use tracing::error;
 use tracing::warn;
 
@@ -25,19 +36,148 @@ use crate::InvalidationTime;
 mod error;
 use error::Error;
 
-async fn authenticate_with_upstream(upstream: &mut Client, scope: &str) -> Result<(), dkregistry::errors::Error> {
-	upstream.authenticate(&[scope]).await?;
-	Ok(())
+/// Smallest margin we tolerate before a cached token's deadline: if less than this remains
+/// we treat the token as expired and re-authenticate rather than risk using it mid-request.
+const TOKEN_REFRESH_SKEW: Duration = Duration::from_secs(10);
+
+/// `dkregistry::v2::Client` doesn't expose the negotiated token's `expires_in`/`exp` after
+/// `authenticate`, so we can't read back a real deadline. Per the distribution auth spec a
+/// token's lifetime defaults to 60s when the auth server omits `expires_in`, so assume the
+/// same conservative bound for every token and lean on `TOKEN_REFRESH_SKEW` to re-authenticate
+/// well before a longer-lived token would actually expire.
+const DEFAULT_TOKEN_TTL: Duration = Duration::from_secs(60);
+
+struct CachedToken {
+	client: Client,
+	deadline: Instant
+}
+
+pub type TokenCache = Arc<DashMap<String, CachedToken>>;
+
+async fn authenticate_with_upstream(upstream: &Client, tokens: &TokenCache, cache_key: &str, scope: &str) -> Result<Client, dkregistry::errors::Error> {
+	if let Some(cached) = tokens.get(cache_key) {
+		if cached.deadline > Instant::now() + TOKEN_REFRESH_SKEW {
+			return Ok(cached.client.clone());
+		}
+	}
+
+	let mut client = upstream.clone();
+	client.authenticate(&[scope]).await?;
+	tokens.insert(cache_key.to_string(), CachedToken { client: client.clone(), deadline: Instant::now() + DEFAULT_TOKEN_TTL });
+	Ok(client)
+}
+
+/// Splits an image name like `ghcr.io/owner/repo` into its upstream registry host and the
+/// repository name dkregistry expects, using the same heuristic Docker itself uses to tell
+/// a registry host apart from the first path segment of a Docker Hub image: the host
+/// component contains a `.` or a `:`, or is exactly `localhost`.
+fn split_registry_host(name: &str) -> (Option<&str>, &str) {
+	match name.split_once('/') {
+		Some((host, rest)) if host.contains('.') || host.contains(':') || host == "localhost" => (Some(host), rest),
+		_ => (None, name)
+	}
+}
+
+/// Per-registry-host upstream credentials, loaded from config the same way docker's
+/// per-host `auths` entries work: one username/password pair per host.
+#[derive(Debug, Deserialize)]
+pub struct UpstreamCredentials {
+	pub username: Option<String>,
+	pub password: Option<String>
+}
+
+/// Resolves an upstream `Client` for an image name, routing `ghcr.io/...`, `quay.io/...`,
+/// etc. to the client registered for that host and falling back to `default_host` for bare
+/// names, so a single deployment can mirror more than one registry. A host with no registered
+/// client also falls back to `default_host`, with the host prefix stripped from the name.
+pub struct UpstreamRegistry {
+	default_host: ArcStr,
+	clients: DashMap<ArcStr, Client>
 }
 
-pub async fn root(upstream: web::Data<Client>) -> Result<&'static str, Error> {
-	Arc::make_mut(&mut upstream.into_inner())
-		.clone()
-		.authenticate(&[])
-		.await?;
+impl UpstreamRegistry {
+	pub fn new(default_host: impl Into<ArcStr>, default_client: Client) -> Self {
+		let default_host = default_host.into();
+		let clients = DashMap::new();
+		clients.insert(default_host.clone(), default_client);
+		Self { default_host, clients }
+	}
+
+	pub fn insert(&self, host: impl Into<ArcStr>, client: Client) {
+		self.clients.insert(host.into(), client);
+	}
+
+	/// Builds one `Client` per entry of a `{host: {username, password}}` credentials map —
+	/// the same shape as docker's per-registry `auths` config — plus the default registry,
+	/// so private mirrors can be configured alongside the public default.
+	pub fn from_credentials(default_host: impl Into<ArcStr>, default_client: Client, credentials: std::collections::HashMap<String, UpstreamCredentials>, insecure: bool) -> Result<Self, dkregistry::errors::Error> {
+		let registry = Self::new(default_host, default_client);
+		for (host, creds) in credentials {
+			let mut config = dkregistry::v2::Config::default().registry(&host).insecure_registry(insecure);
+			if let Some(username) = creds.username {
+				config = config.username(Some(username));
+			}
+			if let Some(password) = creds.password {
+				config = config.password(Some(password));
+			}
+			registry.insert(host, config.build()?);
+		}
+		Ok(registry)
+	}
+
+	fn resolve<'a>(&self, name: &'a str) -> (ArcStr, Client, &'a str) {
+		let (host, rest) = split_registry_host(name);
+		let host: ArcStr = host.map(ArcStr::from).unwrap_or_else(|| self.default_host.clone());
+		match self.clients.get(host.as_str()) {
+			Some(client) => (host.clone(), client.clone(), rest),
+			None => {
+				warn!("no upstream registered for {}; falling back to {}", host, self.default_host);
+				let client = self.clients.get(self.default_host.as_str()).expect("default upstream always registered").clone();
+				(self.default_host.clone(), client, rest)
+			}
+		}
+	}
+}
+
+pub async fn root(upstream: web::Data<UpstreamRegistry>) -> Result<&'static str, Error> {
+	let (_, mut client, _) = upstream.resolve("");
+	client.authenticate(&[]).await?;
 	Ok("")
 }
 
+/// Classifies a request path into the `kind` label used across the metrics in this module.
+fn request_kind(path: &str) -> &'static str {
+	if path.contains("/manifests/") {
+		"manifest"
+	} else if path.contains("/blobs/") {
+		"blob"
+	} else if path.ends_with("/tags/list") {
+		"tags"
+	} else if path.ends_with("/_catalog") {
+		"catalog"
+	} else {
+		"other"
+	}
+}
+
+/// Actix middleware recording total request count and latency, labelled by object kind and
+/// response status, so dashboards can break proxy traffic down the same way operators think
+/// about it: how many manifest/blob/tag requests came through, and how long they took.
+pub async fn track_request_metrics(req: actix_web::dev::ServiceRequest, next: Next<impl actix_web::body::MessageBody>) -> Result<actix_web::dev::ServiceResponse<impl actix_web::body::MessageBody>, actix_web::Error> {
+	let start = Instant::now();
+	let kind = request_kind(req.path());
+
+	let response = next.call(req).await?;
+
+	histogram!("oci_registry_request_duration_seconds", "kind" => kind).record(start.elapsed().as_secs_f64());
+	counter!("oci_registry_requests_total", "kind" => kind, "status" => response.status().as_u16().to_string()).increment(1);
+	Ok(response)
+}
+
+pub async fn metrics(handle: web::Data<PrometheusHandle>) -> HttpResponse {
+	HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(handle.render())
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ManifestRequest {
 	image: ImageName,
@@ -50,33 +190,144 @@ impl ManifestRequest {
 	}
 }
 
-async fn get_manifest(req: &ManifestRequest, max_age: Duration, repo: &Repository, upstream: web::Data<Client>) -> Result<Manifest, Error> {
+/// Platform hint for resolving a multi-platform manifest list/image index to a single
+/// child manifest; defaults to linux/amd64 when a hint is given but a field is omitted.
+#[derive(Debug, Deserialize)]
+pub struct PlatformQuery {
+	os: Option<String>,
+	arch: Option<String>,
+	variant: Option<String>
+}
+
+impl PlatformQuery {
+	fn is_empty(&self) -> bool {
+		self.os.is_none() && self.arch.is_none() && self.variant.is_none()
+	}
+}
+
+/// Resolves the manifest for `req`, returning `None` when a platform was requested but the
+/// resolved manifest list/image index has no matching entry — the caller should treat that as
+/// not found rather than falling back to the list itself.
+async fn get_manifest(req: &ManifestRequest, max_age: Duration, repo: &Repository, upstream: &UpstreamRegistry, tokens: &TokenCache, platform: Option<&PlatformQuery>) -> Result<Option<Manifest>, Error> {
 	let path = req.path();
 	let path = path.strip_prefix("/").unwrap();
-	match repo.clone().read(&path, max_age).await {
-		Ok(stream) => {
+	// Label metrics by upstream registry host, not by image: the set of configured upstreams is
+	// small and operator-controlled, while image names are effectively unbounded and would blow
+	// up Prometheus's series cardinality.
+	let (registry, _, _) = upstream.resolve(req.image.as_ref());
+
+	let manifest = match repo.clone().read(&path, max_age, None).await {
+		Ok((_, stream)) => {
 			let body = stream.try_collect::<web::BytesMut>().await?;
-			let manifest = serde_json::from_slice(body.as_ref())?;
-			return Ok(manifest);
+			counter!("oci_registry_cache_hits_total", "kind" => "manifest", "registry" => registry.to_string()).increment(1);
+			counter!("oci_registry_bytes_served_total", "kind" => "manifest", "source" => "cache").increment(body.len() as u64);
+			serde_json::from_slice(body.as_ref())?
 		},
-		Err(e) => warn!("{} not found in repository ({}); pulling from upstream", path, e)
+		Err(e) => {
+			warn!("{} not found in repository ({}); pulling from upstream", path, e);
+			counter!("oci_registry_cache_misses_total", "kind" => "manifest", "registry" => registry.to_string()).increment(1);
+
+			let (host, client, name) = upstream.resolve(req.image.as_ref());
+			let scope = format!("repository:{}:pull", name);
+			let mut upstream_client = authenticate_with_upstream(&client, tokens, &format!("{}|{}", host, scope), &scope).await?;
+			let upstream_start = Instant::now();
+			let (raw, media_type, digest) = upstream_client.get_raw_manifest_and_metadata(name, &req.reference.to_string()).await?;
+			histogram!("oci_registry_upstream_request_duration_seconds", "kind" => "manifest", "registry" => registry.to_string()).record(upstream_start.elapsed().as_secs_f64());
+			let manifest = Manifest::new(raw, media_type, digest);
+			counter!("oci_registry_bytes_served_total", "kind" => "manifest", "source" => "proxy").increment(manifest.manifest.len() as u64);
+
+			let body = serde_json::to_vec(&manifest).unwrap();
+			let len = body.len().try_into().unwrap_or(i64::MAX);
+			if let Err(e) = repo.write(&path, stream::iter(iter::once(Result::<_, std::io::Error>::Ok(body.into()))), len).await {
+				error!("{}", e);
+			}
+			manifest
+		}
+	};
+
+	let platform = match platform {
+		Some(p) if is_manifest_list(&manifest.media_type.to_string()) => p,
+		_ => return Ok(Some(manifest))
+	};
+
+	let (host, client, name) = upstream.resolve(req.image.as_ref());
+	let scope = format!("repository:{}:pull", name);
+	let mut upstream_client = authenticate_with_upstream(&client, tokens, &format!("{}|{}", host, scope), &scope).await?;
+	// `resolve_platform_manifest` returning `None` means the list has no entry for the
+	// requested platform; propagate that as-is instead of falling back to the unresolved list,
+	// which a client that explicitly negotiated a platform shouldn't have to re-resolve itself.
+	Ok(resolve_platform_manifest(repo, &mut upstream_client, name, &manifest, platform).await?)
+}
+
+/// Media types upstream uses for a multi-platform manifest list / OCI image index.
+fn is_manifest_list(media_type: &str) -> bool {
+	matches!(media_type, "application/vnd.docker.distribution.manifest.list.v2+json" | "application/vnd.oci.image.index.v1+json")
+}
+
+#[derive(Debug, Deserialize)]
+struct ManifestListPlatform {
+	os: Option<String>,
+	architecture: Option<String>,
+	variant: Option<String>
+}
+
+#[derive(Debug, Deserialize)]
+struct ManifestListEntry {
+	digest: String,
+	platform: Option<ManifestListPlatform>
+}
+
+#[derive(Debug, Deserialize)]
+struct ManifestListBody {
+	manifests: Vec<ManifestListEntry>
+}
+
+/// Picks the child manifest matching `platform` out of a manifest list/image index and
+/// fetches it (from cache if we've already resolved it, from upstream otherwise), caching
+/// the child under its own digest since digest-addressed content never needs invalidating.
+/// Returns `None` if the list has no entry for the requested platform.
+async fn resolve_platform_manifest(repo: &Repository, upstream: &mut Client, image: &str, list: &Manifest, platform: &PlatformQuery) -> Result<Option<Manifest>, Error> {
+	let os = platform.os.as_deref().unwrap_or("linux");
+	let arch = platform.arch.as_deref().unwrap_or("amd64");
+	let variant = platform.variant.as_deref();
+
+	let parsed: ManifestListBody = serde_json::from_slice(&list.manifest)?;
+	let entry = parsed.manifests.into_iter().find(|m| match &m.platform {
+		Some(p) => p.os.as_deref() == Some(os) && p.architecture.as_deref() == Some(arch) && variant.map_or(true, |v| p.variant.as_deref() == Some(v)),
+		None => false
+	});
+	let entry = match entry {
+		Some(e) => e,
+		None => return Ok(None)
+	};
+
+	let child_path = format!("{}/manifests/{}", image, entry.digest);
+	if let Ok((_, stream)) = repo.clone().read(&child_path, Duration::MAX, None).await {
+		let body = stream.try_collect::<web::BytesMut>().await?;
+		return Ok(Some(serde_json::from_slice(body.as_ref())?));
 	}
 
-	let mut upstream = (*upstream.into_inner()).clone();
-	authenticate_with_upstream(&mut upstream, &format!("repository:{}:pull", req.image.as_ref())).await?;
-	let (manifest, media_type, digest) = upstream.get_raw_manifest_and_metadata(req.image.as_ref(), &req.reference.to_string()).await?;
-	let manifest = Manifest::new(manifest, media_type, digest);
+	let (raw, media_type, digest) = upstream.get_raw_manifest_and_metadata(image, &entry.digest).await?;
+	let child = Manifest::new(raw, media_type, digest);
 
-	let body = serde_json::to_vec(&manifest).unwrap();
+	let body = serde_json::to_vec(&child).unwrap();
 	let len = body.len().try_into().unwrap_or(i64::MAX);
-	if let Err(e) = repo.write(&path, stream::iter(iter::once(Result::<_, std::io::Error>::Ok(body.into()))), len).await {
+	if let Err(e) = repo.write(&child_path, stream::iter(iter::once(Result::<_, std::io::Error>::Ok(body.into()))), len).await {
 		error!("{}", e);
 	}
-	Ok(manifest)
+	Ok(Some(child))
 }
 
-pub async fn manifest(path: web::Path<ManifestRequest>, invalidation: web::Data<InvalidationTime>, repo: web::Data<Repository>, upstream: web::Data<Client>) -> Result<HttpResponse, Error> {
-	let manifest = get_manifest(path.as_ref(), invalidation.manifest, repo.as_ref(), upstream).await?;
+pub async fn manifest(path: web::Path<ManifestRequest>, platform: web::Query<PlatformQuery>, invalidation: web::Data<InvalidationTime>, repo: web::Data<Repository>, upstream: web::Data<UpstreamRegistry>, tokens: web::Data<TokenCache>) -> Result<HttpResponse, Error> {
+	let platform = platform.into_inner();
+	let platform = if platform.is_empty() { None } else { Some(platform) };
+	let manifest = match get_manifest(path.as_ref(), invalidation.manifest, repo.as_ref(), upstream.as_ref(), tokens.as_ref(), platform.as_ref()).await? {
+		Some(manifest) => manifest,
+		// A platform was requested but the resolved manifest list/image index has no matching
+		// entry; report it as missing rather than handing back the list for the client to
+		// re-resolve itself.
+		None => return Ok(HttpResponse::NotFound().finish())
+	};
 
 	let mut response = HttpResponse::Ok();
 	response.insert_header((http::header::CONTENT_TYPE, manifest.media_type.to_string()));
@@ -98,51 +349,382 @@ impl BlobRequest {
 	}
 }
 
-pub async fn blob(path: web::Path<BlobRequest>, invalidation: web::Data<InvalidationTime>, repo: web::Data<Repository>, upstream: web::Data<Client>) -> Result<HttpResponse, Error> {
+#[derive(Debug, Clone, Copy)]
+enum ByteRange {
+	/// `bytes=start-` or `bytes=start-end`.
+	Range { start: u64, end: Option<u64> },
+	/// `bytes=-length`: the last `length` bytes of the resource. Resolving this needs the
+	/// resource's total length, which isn't known yet at parse time; see `blob`, which rejects
+	/// this variant with `416` rather than resolving it.
+	Suffix(u64)
+}
+
+fn parse_range(header: &http::header::HeaderValue) -> Option<ByteRange> {
+	let header = header.to_str().ok()?;
+	let spec = header.strip_prefix("bytes=")?;
+	let (start, end) = spec.split_once('-')?;
+	if start.is_empty() {
+		return Some(ByteRange::Suffix(end.parse().ok()?));
+	}
+	let start = start.parse().ok()?;
+	let end = if end.is_empty() { None } else { Some(end.parse().ok()?) };
+	Some(ByteRange::Range { start, end })
+}
+
+fn slice_stream<S>(mut stream: S, start: u64, end: u64) -> impl futures::Stream<Item = Result<web::Bytes, ArcError>>
+where S: futures::Stream<Item = Result<web::Bytes, ArcError>> + Unpin
+{
+	let mut pos = 0u64;
+	let mut remaining = Some(end.saturating_sub(start) + 1);
+	let mut draining = false;
+	stream::poll_fn(move |cx| loop {
+		if remaining == Some(0) {
+			// Every byte in the requested window has been yielded, but on a proxied blob this
+			// stream is one tee of a broadcast that the upstream producer may still append a
+			// digest-mismatch error to (see `blob`). Keep draining — without yielding any more
+			// chunks — so that error still reaches a ranged client instead of the stream quietly
+			// ending as if the (still unverified) range had checked out.
+			draining = true;
+			remaining = None;
+		}
+		match futures::ready!(stream.poll_next_unpin(cx)) {
+			Some(Ok(_)) if draining => continue,
+			Some(Ok(chunk)) => {
+				let chunk_start = pos;
+				pos += chunk.len() as u64;
+				if pos <= start {
+					continue;
+				}
+				let local_start = start.saturating_sub(chunk_start) as usize;
+				let mut chunk = chunk.slice(local_start..);
+				if let Some(rem) = remaining {
+					if chunk.len() as u64 > rem {
+						chunk = chunk.slice(..rem as usize);
+					}
+					remaining = Some(rem - chunk.len() as u64);
+				}
+				return std::task::Poll::Ready(Some(Ok(chunk)));
+			},
+			Some(Err(e)) => return std::task::Poll::Ready(Some(Err(e))),
+			None => return std::task::Poll::Ready(None)
+		}
+	})
+}
+
+pub async fn blob(req: HttpRequest, path: web::Path<BlobRequest>, invalidation: web::Data<InvalidationTime>, repo: web::Data<Repository>, upstream: web::Data<UpstreamRegistry>, tokens: web::Data<TokenCache>) -> Result<HttpResponse, Error> {
 	if(!path.digest.starts_with("sha256:")) {
 		return Err(Error::InvalidDigest);
 	}
 
+	// We don't support suffix ranges (`bytes=-N`): resolving one needs the object's total
+	// length, which isn't known until after we've already looked it up in cache or upstream.
+	// Reject with 416 rather than letting it fall through `parse_range`'s `None` case, which
+	// would silently degrade it to a full 200.
+	let range = match req.headers().get(http::header::RANGE).and_then(parse_range) {
+		Some(ByteRange::Range { start, end }) => Some((start, end)),
+		Some(ByteRange::Suffix(_)) => return Ok(HttpResponse::RangeNotSatisfiable().finish()),
+		None => None
+	};
+
 	let req_path = path.path();
 	let storage_path = req_path.strip_prefix("/").unwrap();
-	match (*repo.clone().into_inner()).clone().read(storage_path, invalidation.blob).await {
-		Ok(stream) => return Ok(HttpResponse::Ok().streaming(stream)),
+	// Label metrics by upstream registry host rather than image; see the equivalent comment in
+	// get_manifest for why.
+	let (registry, _, _) = upstream.resolve(path.image.as_ref());
+	match (*repo.clone().into_inner()).clone().read(storage_path, invalidation.blob, range).await {
+		Ok((total_len, stream)) => {
+			counter!("oci_registry_cache_hits_total", "kind" => "blob", "registry" => registry.to_string()).increment(1);
+			let total_len = total_len.max(0) as u64;
+			// `repo.read`'s returned length is the full object size (it's what we put in
+			// Content-Range's total), not the size of the requested window, so a Range request
+			// must count only the bytes actually sliced out and streamed to the client, and must
+			// clamp `end` (and reject `start`) against that size rather than trusting the client.
+			let mut response = match range {
+				Some((start, _)) if start >= total_len => {
+					return Ok(HttpResponse::RangeNotSatisfiable()
+						.insert_header((http::header::CONTENT_RANGE, format!("bytes */{}", total_len)))
+						.finish());
+				},
+				Some((start, end)) => {
+					let end = end.unwrap_or_else(|| total_len.saturating_sub(1)).min(total_len.saturating_sub(1));
+					let served = end.saturating_sub(start).saturating_add(1);
+					counter!("oci_registry_bytes_served_total", "kind" => "blob", "source" => "cache").increment(served);
+					let mut response = HttpResponse::PartialContent();
+					response.insert_header((http::header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, total_len)));
+					response
+				},
+				None => {
+					counter!("oci_registry_bytes_served_total", "kind" => "blob", "source" => "cache").increment(total_len);
+					HttpResponse::Ok()
+				}
+			};
+			response.insert_header((http::header::ACCEPT_RANGES, "bytes"));
+			return Ok(response.streaming(stream));
+		},
 		Err(e) => warn!("{} not found in repository ({}); pulling from upstream", storage_path, e)
 	};
+	counter!("oci_registry_cache_misses_total", "kind" => "blob", "registry" => registry.to_string()).increment(1);
+
+	let (host, client, name) = upstream.resolve(path.image.as_ref());
+	let scope = format!("repository:{}:pull", name);
+	let mut upstream = authenticate_with_upstream(&client, &tokens, &format!("{}|{}", host, scope), &scope).await?;
+	let upstream_start = Instant::now();
+	let response = upstream.get_blob_response(name, path.digest.as_ref()).await?;
+	histogram!("oci_registry_upstream_request_duration_seconds", "kind" => "blob", "registry" => registry.to_string()).record(upstream_start.elapsed().as_secs_f64());
 
-	let mut upstream = (*upstream.into_inner()).clone();
-	authenticate_with_upstream(&mut upstream, &format!("repository:{}:pull", path.image.as_ref())).await?;
-	let response = upstream.get_blob_response(path.image.as_ref(), path.digest.as_ref()).await?;
+	let expected_digest = path.digest.strip_prefix("sha256:").unwrap_or(&path.digest).to_string();
 
 	let len = response.size().unwrap_or_default();
 	let (tx, rx) = async_broadcast::broadcast(16);
+	let (verified_tx, verified_rx) = tokio::sync::oneshot::channel::<bool>();
 	{
 		let req_path = req_path.clone();
 		let mut stream = response.stream();
+		let registry = registry.clone();
+		gauge!("oci_registry_inflight_proxied_blobs").increment(1.0);
 		rt::spawn(async move {
+			let mut hasher = Sha256::new();
+			let mut bytes_proxied = 0u64;
+			let mut completed = true;
 			while let Some(chunk) = stream.next().await {
 				let chunk = match chunk {
-					Ok(v) => Ok(v),
+					Ok(v) => {
+						hasher.update(&v);
+						bytes_proxied += v.len() as u64;
+						Ok(v)
+					},
 					Err(e) => {
 						error!("Error reading from upstream:  {}", e);
 						Err(ArcError::from(e))
 					}
 				};
+				let failed = chunk.is_err();
 				if let Err(_) = tx.broadcast(chunk).await {
 					error!("Readers for proxied blob request {} all closed", req_path);
+					completed = false;
 					break;
 				}
+				if failed {
+					completed = false;
+					break;
+				}
+			}
+
+			counter!("oci_registry_bytes_served_total", "kind" => "blob", "source" => "proxy", "registry" => registry.to_string()).increment(bytes_proxied);
+			gauge!("oci_registry_inflight_proxied_blobs").decrement(1.0);
+
+			if !completed {
+				let _ = verified_tx.send(false);
+				return;
+			}
+
+			let actual_digest = hex::encode(hasher.finalize());
+			if actual_digest != expected_digest {
+				error!("Digest mismatch for {}: expected sha256:{}, got sha256:{}", req_path, expected_digest, actual_digest);
+				let mismatch = std::io::Error::new(std::io::ErrorKind::InvalidData, "upstream blob did not match requested digest");
+				let _ = tx.broadcast(Err(ArcError::from(mismatch))).await;
+				let _ = verified_tx.send(false);
+			} else {
+				let _ = verified_tx.send(true);
 			}
 		});
 	}
 
 	let rx2 = rx.clone();
+	let storage_path = req_path.strip_prefix("/").unwrap().to_string();
 	rt::spawn(async move {
-		if let Err(e) = repo.write(req_path.strip_prefix("/").unwrap(), rx2, len.try_into().unwrap_or(i64::MAX)).await {
-			error!("{}", e);
+		// `verified_tx` only sends `true` once the whole upstream body has been hashed and
+		// matched, which is after every byte has been handed to `repo.write` (and, depending on
+		// the backend, may already have been committed). Treat any non-`Ok(true)` outcome, and a
+		// failed write, as "don't trust what's on disk" and explicitly delete it rather than
+		// relying on the broadcast stream itself to abort the write.
+		match repo.write(&storage_path, rx2, len.try_into().unwrap_or(i64::MAX)).await {
+			Ok(()) => match verified_rx.await {
+				Ok(true) => {},
+				Ok(false) | Err(_) => {
+					warn!("Deleting {} from cache after failed digest verification", storage_path);
+					if let Err(e) = repo.delete(&storage_path).await {
+						error!("Failed to delete corrupt blob {} from cache: {}", storage_path, e);
+					}
+				}
+			},
+			Err(e) => {
+				error!("{}", e);
+				if let Err(e) = repo.delete(&storage_path).await {
+					error!("Failed to delete partial blob {} from cache: {}", storage_path, e);
+				}
+			}
 		}
 	});
 
-	Ok(HttpResponse::Ok().streaming(rx))
+	match range {
+		Some((start, _)) if start >= len => Ok(HttpResponse::RangeNotSatisfiable()
+			.insert_header((http::header::CONTENT_RANGE, format!("bytes */{}", len)))
+			.finish()),
+		Some((start, end)) => {
+			let end = end.unwrap_or_else(|| len.saturating_sub(1)).min(len.saturating_sub(1));
+			let mut response = HttpResponse::PartialContent();
+			response.insert_header((http::header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len)));
+			response.insert_header((http::header::ACCEPT_RANGES, "bytes"));
+			// This 206 is served before the upstream body has been fully hashed, so a mismatch
+			// is only caught mid-stream (via slice_stream's post-range draining above) rather
+			// than before any bytes go out — there's no way to verify a streamed digest before
+			// the client has already seen the start of the range.
+			Ok(response.streaming(slice_stream(rx, start, end)))
+		},
+		None => Ok(HttpResponse::Ok().insert_header((http::header::ACCEPT_RANGES, "bytes")).streaming(rx))
+	}
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListingQuery {
+	n: Option<u32>,
+	last: Option<String>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TagsList {
+	name: String,
+	tags: Vec<String>
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TagsRequest {
+	image: ImageName
+}
+
+impl TagsRequest {
+	fn path(&self) -> String {
+		format!("/{}/tags/list", self.image)
+	}
+}
+
+async fn get_tags(req: &TagsRequest, query: &ListingQuery, max_age: Duration, repo: &Repository, upstream: &UpstreamRegistry, tokens: &TokenCache) -> Result<(TagsList, Option<String>), Error> {
+	let path = req.path();
+	let path = path.strip_prefix("/").unwrap();
+	// Label metrics by upstream registry host rather than image; see the equivalent comment in
+	// get_manifest for why.
+	let (registry, _, _) = upstream.resolve(req.image.as_ref());
+	let paginated = query.n.is_some() || query.last.is_some();
+	if !paginated {
+		match repo.clone().read(path, max_age, None).await {
+			Ok((_, stream)) => {
+				let body = stream.try_collect::<web::BytesMut>().await?;
+				counter!("oci_registry_cache_hits_total", "kind" => "tags", "registry" => registry.to_string()).increment(1);
+				let tags = serde_json::from_slice(body.as_ref())?;
+				return Ok((tags, None));
+			},
+			Err(e) => warn!("{} not found in repository ({}); pulling from upstream", path, e)
+		}
+	}
+	counter!("oci_registry_cache_misses_total", "kind" => "tags", "registry" => registry.to_string()).increment(1);
+
+	let (host, client, name) = upstream.resolve(req.image.as_ref());
+	let scope = format!("repository:{}:pull", name);
+	let mut upstream = authenticate_with_upstream(&client, tokens, &format!("{}|{}", host, scope), &scope).await?;
+	// dkregistry's `get_tags` only takes a page size, not a resume cursor, so `last` can't be
+	// forwarded upstream. Fetch the whole list and apply both `last` and `n` ourselves instead
+	// of asking upstream for just the first `n` tags: doing that and then skipping past `last`
+	// would silently return an empty page (and drop the `rel="next"` link) for any `last` that
+	// names a tag past the first page.
+	let all_tags: Vec<String> = upstream.get_tags(name, None).try_collect().await?;
+	let remaining: Vec<String> = match &query.last {
+		Some(last) => all_tags.into_iter().skip_while(|t| t <= last).collect(),
+		None => all_tags
+	};
+	let has_more = query.n.map(|n| remaining.len() as u32 > n).unwrap_or(false);
+	let tags: Vec<String> = match query.n {
+		Some(n) => remaining.into_iter().take(n as usize).collect(),
+		None => remaining
+	};
+
+	let link = has_more.then(|| {
+		tags.last().map(|last| format!("</v2/{}/tags/list?n={}&last={}>; rel=\"next\"", req.image.as_ref(), query.n.unwrap(), last))
+	}).flatten();
+
+	let tags = TagsList { name: req.image.as_ref().to_string(), tags };
+	if !paginated {
+		let body = serde_json::to_vec(&tags).unwrap();
+		let len = body.len().try_into().unwrap_or(i64::MAX);
+		if let Err(e) = repo.write(path, stream::iter(iter::once(Result::<_, std::io::Error>::Ok(body.into()))), len).await {
+			error!("{}", e);
+		}
+	}
+	Ok((tags, link))
+}
+
+pub async fn tags(path: web::Path<TagsRequest>, query: web::Query<ListingQuery>, invalidation: web::Data<InvalidationTime>, repo: web::Data<Repository>, upstream: web::Data<UpstreamRegistry>, tokens: web::Data<TokenCache>) -> Result<HttpResponse, Error> {
+	let (tags, link) = get_tags(path.as_ref(), query.as_ref(), invalidation.manifest, repo.as_ref(), upstream.as_ref(), tokens.as_ref()).await?;
+
+	let mut response = HttpResponse::Ok();
+	if let Some(link) = link {
+		response.insert_header((http::header::LINK, link));
+	}
+	Ok(response.json(tags))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CatalogList {
+	repositories: Vec<String>
+}
+
+async fn get_catalog(query: &ListingQuery, max_age: Duration, repo: &Repository, upstream: &UpstreamRegistry, tokens: &TokenCache) -> Result<(CatalogList, Option<String>), Error> {
+	let path = "_catalog";
+	let paginated = query.n.is_some() || query.last.is_some();
+	if !paginated {
+		match repo.clone().read(path, max_age, None).await {
+			Ok((_, stream)) => {
+				let body = stream.try_collect::<web::BytesMut>().await?;
+				counter!("oci_registry_cache_hits_total", "kind" => "catalog").increment(1);
+				let catalog = serde_json::from_slice(body.as_ref())?;
+				return Ok((catalog, None));
+			},
+			Err(e) => warn!("{} not found in repository ({}); pulling from upstream", path, e)
+		}
+	}
+	counter!("oci_registry_cache_misses_total", "kind" => "catalog").increment(1);
+
+	// The catalog has no image name to route by, so it always targets the default upstream registry.
+	let (host, client, _) = upstream.resolve("");
+	let scope = "registry:catalog:*";
+	let mut upstream = authenticate_with_upstream(&client, tokens, &format!("{}|{}", host, scope), scope).await?;
+	// Same caveat as `get_tags`: dkregistry's `get_catalog` takes only a page size, not a resume
+	// cursor. Fetch the whole catalog and apply both `last` and `n` ourselves rather than asking
+	// upstream for just the first `n` repositories and then skipping past `last`, which would
+	// silently return an empty page for any `last` beyond the first page.
+	let all_repositories: Vec<String> = upstream.get_catalog(None).try_collect().await?;
+	let remaining: Vec<String> = match &query.last {
+		Some(last) => all_repositories.into_iter().skip_while(|r| r <= last).collect(),
+		None => all_repositories
+	};
+	let has_more = query.n.map(|n| remaining.len() as u32 > n).unwrap_or(false);
+	let repositories: Vec<String> = match query.n {
+		Some(n) => remaining.into_iter().take(n as usize).collect(),
+		None => remaining
+	};
+
+	let link = has_more.then(|| {
+		repositories.last().map(|last| format!("</v2/_catalog?n={}&last={}>; rel=\"next\"", query.n.unwrap(), last))
+	}).flatten();
+
+	let catalog = CatalogList { repositories };
+	if !paginated {
+		let body = serde_json::to_vec(&catalog).unwrap();
+		let len = body.len().try_into().unwrap_or(i64::MAX);
+		if let Err(e) = repo.write(path, stream::iter(iter::once(Result::<_, std::io::Error>::Ok(body.into()))), len).await {
+			error!("{}", e);
+		}
+	}
+	Ok((catalog, link))
+}
+
+pub async fn catalog(query: web::Query<ListingQuery>, invalidation: web::Data<InvalidationTime>, repo: web::Data<Repository>, upstream: web::Data<UpstreamRegistry>, tokens: web::Data<TokenCache>) -> Result<HttpResponse, Error> {
+	let (catalog, link) = get_catalog(query.as_ref(), invalidation.manifest, repo.as_ref(), upstream.as_ref(), tokens.as_ref()).await?;
+
+	let mut response = HttpResponse::Ok();
+	if let Some(link) = link {
+		response.insert_header((http::header::LINK, link));
+	}
+	Ok(response.json(catalog))
 }
 